@@ -1,11 +1,16 @@
+mod protocol;
+mod transport;
+
 use clap::{self, Arg, Command};
 use env_logger::Builder;
 use log::LevelFilter;
+use protocol::{Parser, Response};
 use rand::Rng;
 use serialport;
 use std::io::Read;
 use std::io::Write;
 use std::time::Duration;
+use transport::Transport;
 
 const RETRIES: u8 = 5;
 
@@ -17,10 +22,19 @@ fn get_args() -> clap::ArgMatches {
         .arg(
             Arg::new("Serial_Port")
                 .help("The Serial Port address for the Device")
-                .required(true)
+                .required_unless_present("Remote")
                 .takes_value(true)
                 .value_name("ADDRESS"),
         )
+        .arg(
+            Arg::new("Remote")
+                .short('m')
+                .long("remote")
+                .help("Connect to a remote device over TCP instead of a serial port")
+                .takes_value(true)
+                .value_name("HOST:PORT")
+                .conflicts_with("Serial_Port"),
+        )
         .arg(
             Arg::new("Baud_Rate")
                 .short('B')
@@ -56,7 +70,7 @@ fn get_args() -> clap::ArgMatches {
             Arg::new("Raw")
                 .short('r')
                 .long("raw")
-                .help("Send a payload directly")
+                .help("Send a payload directly, bypassing CRC framing (reply shown as raw bytes)")
                 .takes_value(true)
                 .value_name("PAYLOAD")
                 .conflicts_with_all(&["Test", "Next", "Following", "Add"]),
@@ -110,6 +124,30 @@ fn get_args() -> clap::ArgMatches {
                 .help("Randomize the color for a new Task")
                 .conflicts_with("Color"),
         )
+        .arg(
+            Arg::new("Listen")
+                .short('l')
+                .long("listen")
+                .help("Stay connected and log events the device pushes on its own")
+                .conflicts_with_all(&["Test", "Raw", "Next", "Following", "Add", "Swap"]),
+        )
+        .arg(
+            Arg::new("Script")
+                .short('S')
+                .long("script")
+                .help("Execute a queue of operations from a file (or - for stdin)")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&[
+                    "Test", "Raw", "Next", "Following", "Add", "Swap", "Listen",
+                ]),
+        )
+        .arg(
+            Arg::new("Keep_Going")
+                .long("keep-going")
+                .help("Continue the script past a failed command instead of aborting")
+                .requires("Script"),
+        )
         .get_matches()
 }
 
@@ -124,11 +162,12 @@ fn init_communication(
     address: &str,
     baud: u32,
     timeout: Duration,
-) -> Result<Box<dyn serialport::SerialPort>, serialport::Error> {
-    serialport::new(address, baud)
+) -> Result<Box<dyn Transport>, serialport::Error> {
+    let port = serialport::new(address, baud)
         .timeout(timeout)
         .flow_control(serialport::FlowControl::Software)
-        .open()
+        .open()?;
+    Ok(Box::new(transport::SerialTransport(port)))
 }
 
 fn main() {
@@ -155,65 +194,304 @@ fn main() {
         .expect("Unexpected")
         .parse::<u64>()
         .expect("Provided Timeout must be an integer");
-    let address = args.value_of("Serial_Port").expect("Unexpected");
+    let timeout = Duration::from_millis(timeout);
 
-    let mut device = match init_communication(&address, baud, Duration::from_millis(timeout)) {
-        Ok(val) => val,
-        Err(err) => panic!(
-            "Failed to initialize communication with the Device! Reason: {}",
-            err
-        ),
+    let mut device: Box<dyn Transport> = if let Some(remote) = args.value_of("Remote") {
+        match transport::connect_tcp(remote, timeout) {
+            Ok(val) => val,
+            Err(err) => panic!(
+                "Failed to initialize communication with the Device! Reason: {}",
+                err
+            ),
+        }
+    } else {
+        let address = args.value_of("Serial_Port").expect("Unexpected");
+        match init_communication(address, baud, timeout) {
+            Ok(val) => val,
+            Err(err) => panic!(
+                "Failed to initialize communication with the Device! Reason: {}",
+                err
+            ),
+        }
     };
 
-    device.write_data_terminal_ready(false).unwrap();
-    device.write_request_to_send(false).unwrap();
+    device.handshake().expect("Failed to initialize the link");
 
-    if args.is_present("Test") {
-        test(&mut device)
+    if args.is_present("Script") {
+        let source = args.value_of("Script").expect("Unexpected");
+        if let Err(err) = run_script(device.as_mut(), source, args.is_present("Keep_Going")) {
+            log::error!("Failed to run the script: {}", err);
+            std::process::exit(1);
+        }
+    } else if args.is_present("Listen") {
+        if let Err(err) = listen(device.as_mut()) {
+            log::error!("Listener stopped with an error: {}", err);
+        }
+    } else if args.is_present("Test") {
+        test(device.as_mut())
     } else if args.is_present("Raw") {
-        raw(&mut device, args.value_of("Raw").expect("Unexpected"));
+        raw(device.as_mut(), args.value_of("Raw").expect("Unexpected"));
     } else if args.is_present("Next")
         || args.is_present("Swap")
         || args.is_present("Following")
         || args.is_present("Add")
     {
-        for i in 0..RETRIES {
-            let val = if args.is_present("Next") {
-                next(&mut device)
-            } else if args.is_present("Swap") {
-                swap(&mut device)
-            } else if args.is_present("Following") {
-                following(
-                    &mut device,
-                    args.value_of("Following").expect("Unexpected"),
-                    args.value_of("Color").expect("Unexpected"),
-                )
+        let operation = if args.is_present("Next") {
+            Operation::Next
+        } else if args.is_present("Swap") {
+            Operation::Swap
+        } else if args.is_present("Following") {
+            Operation::Following {
+                message: args.value_of("Following").expect("Unexpected").to_string(),
+                color: args.value_of("Color").expect("Unexpected").to_string(),
+            }
+        } else {
+            let color: String = if args.is_present("Random") {
+                let mut rng = rand::thread_rng();
+                let num: u32 = rng.gen_range(0..16777215);
+                format!("#{:X}", num)
             } else {
-                let color: String = if args.is_present("Random") {
-                    let mut rng = rand::thread_rng();
-                    let num: u32 = rng.gen_range(0..16777215);
-                    format!("#{:X}", num)
-                } else {
-                    args.value_of("Color").expect("Unexpected").to_string()
-                };
-                add(
-                    &mut device,
-                    args.value_of("Add").expect("Unexpected"),
-                    &color,
-                )
+                args.value_of("Color").expect("Unexpected").to_string()
             };
-            if val.is_ok() {
+            Operation::Add {
+                message: args.value_of("Add").expect("Unexpected").to_string(),
+                color,
+            }
+        };
+        run_with_retries(|| dispatch_operation(device.as_mut(), &operation));
+    }
+}
+
+/// A single operation the tool can run against the device.
+enum Operation {
+    Next,
+    Swap,
+    Following { message: String, color: String },
+    Add { message: String, color: String },
+}
+
+/// Run `command`, retrying on `Nak`/`CrcMismatch`/timeout and stopping as soon
+/// as the device accepts it. Returns whether the command ultimately succeeded.
+fn run_with_retries<F>(mut command: F) -> bool
+where
+    F: FnMut() -> Result<Response, std::io::Error>,
+{
+    for i in 0..RETRIES {
+        match command() {
+            Ok(Response::Ack) => {
                 log::info!("Success!");
-                break;
-            } else {
-                log::warn!("Failed to communicate! Reason: {:?}", val);
+                return true;
+            }
+            Ok(Response::Data(payload)) => {
+                log::info!("Success! Device replied: {}", payload);
+                return true;
+            }
+            Ok(resp @ (Response::Nak | Response::CrcMismatch)) => {
+                log::warn!("Device rejected the command: {:?}", resp);
+                log::info!("Retrying... {}/{}", i + 1, RETRIES);
+            }
+            Err(err) => {
+                log::warn!("Failed to communicate! Reason: {}", err);
                 log::info!("Retrying... {}/{}", i + 1, RETRIES);
             }
         }
     }
+    false
 }
 
-fn test(device: &mut Box<dyn serialport::SerialPort>) {
+/// Send the command described by `op` once and return the device's reply.
+fn dispatch_operation(
+    device: &mut (impl Transport + ?Sized),
+    op: &Operation,
+) -> Result<Response, std::io::Error> {
+    match op {
+        Operation::Next => next(device),
+        Operation::Swap => swap(device),
+        Operation::Following { message, color } => following(device, message, color),
+        Operation::Add { message, color } => add(device, message, color),
+    }
+}
+
+/// Parse a single script line into an [`Operation`], or `None` if the command
+/// word is not recognised. A trailing `#RRGGBB` token sets the task color.
+fn parse_operation(line: &str) -> Option<Operation> {
+    let (cmd, rest) = match line.split_once(char::is_whitespace) {
+        Some((cmd, rest)) => (cmd, rest.trim()),
+        None => (line, ""),
+    };
+    match cmd.to_lowercase().as_str() {
+        "next" => Some(Operation::Next),
+        "swap" => Some(Operation::Swap),
+        "following" => {
+            let (message, color) = split_task_color(rest);
+            Some(Operation::Following { message, color })
+        }
+        "add" => {
+            let (message, color) = split_task_color(rest);
+            Some(Operation::Add { message, color })
+        }
+        _ => None,
+    }
+}
+
+/// Split the argument of an `add`/`following` line into its task text and an
+/// optional trailing color, defaulting to white when none is given.
+fn split_task_color(rest: &str) -> (String, String) {
+    match rest.rsplit_once(char::is_whitespace) {
+        Some((message, color)) if color.starts_with('#') => {
+            (message.trim().to_string(), color.to_string())
+        }
+        _ => (rest.to_string(), "#FFFFFF".to_string()),
+    }
+}
+
+/// Execute a queue of operations read from `source` (`-` for stdin) over a
+/// single open connection, applying the per-command retry logic. Aborts on the
+/// first failed line unless `keep_going` is set, and exits non-zero if any line
+/// failed so automation can check the result.
+fn run_script(
+    device: &mut (impl Transport + ?Sized),
+    source: &str,
+    keep_going: bool,
+) -> Result<(), std::io::Error> {
+    use std::io::BufRead;
+
+    let reader: Box<dyn BufRead> = if source == "-" {
+        Box::new(std::io::BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(std::io::BufReader::new(std::fs::File::open(source)?))
+    };
+
+    let mut succeeded: usize = 0;
+    let mut failed: usize = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let ok = match parse_operation(trimmed) {
+            Some(op) => run_with_retries(|| dispatch_operation(device, &op)),
+            None => {
+                log::error!("Line {}: unknown operation: {}", index + 1, trimmed);
+                false
+            }
+        };
+        if ok {
+            succeeded += 1;
+        } else {
+            failed += 1;
+            if !keep_going {
+                log::error!("Aborting at line {} after a failed command", index + 1);
+                break;
+            }
+        }
+    }
+
+    log::info!("Script finished: {} succeeded, {} failed", succeeded, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Set by the `SIGINT` handler to request a clean shutdown of the listener.
+static SHUTDOWN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// `SIGINT` handler: only flips the (async-signal-safe) shutdown flag.
+extern "C" fn request_shutdown(_signum: std::os::raw::c_int) {
+    SHUTDOWN.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+extern "C" {
+    fn signal(
+        signum: std::os::raw::c_int,
+        handler: extern "C" fn(std::os::raw::c_int),
+    ) -> extern "C" fn(std::os::raw::c_int);
+}
+
+/// Stay connected and react to events the device pushes on its own. A
+/// dedicated thread owns a clone of the read half of the port and keeps
+/// decoding framed messages, forwarding each one over an `mpsc` channel to
+/// this function, which dispatches it. Ctrl-C sets the [`SHUTDOWN`] flag so
+/// the reader thread leaves its loop and drops its port handle cleanly; the
+/// thread also stops when the link reports EOF or an error.
+fn listen(device: &mut (impl Transport + ?Sized)) -> Result<(), std::io::Error> {
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::{self, RecvTimeoutError};
+
+    // Install the Ctrl-C handler (SIGINT == 2) via the always-linked C runtime.
+    SHUTDOWN.store(false, Ordering::SeqCst);
+    unsafe {
+        signal(2, request_shutdown);
+    }
+
+    let (tx, rx) = mpsc::channel::<Response>();
+
+    let mut reader = device.try_clone()?;
+    let handle = std::thread::spawn(move || {
+        let mut parser = Parser::new();
+        let mut read_buffer = [0u8; 64];
+        while !SHUTDOWN.load(Ordering::SeqCst) {
+            match reader.read(&mut read_buffer) {
+                Ok(0) => {
+                    // EOF on a closed socket: stop rather than busy-spin.
+                    log::warn!("Device closed the connection");
+                    break;
+                }
+                Ok(num) => {
+                    for event in parser.consume(&read_buffer[..num]) {
+                        if tx.send(event).is_err() {
+                            return;
+                        }
+                    }
+                }
+                // A read timeout arrives as TimedOut on serial ports and as
+                // WouldBlock on a TCP socket; both just mean "nothing yet" and
+                // give the loop a chance to observe the shutdown flag.
+                Err(ref err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => {
+                    log::error!("Reader thread stopped: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    log::info!("Listening for device events. Press Ctrl-C to stop.");
+    // Drain events until Ctrl-C is pressed or the reader thread closes the
+    // channel; the short timeout keeps the shutdown flag responsive.
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(event) => dispatch(event),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    log::info!("Shutting down...");
+    let _ = handle.join();
+    Ok(())
+}
+
+/// Log a single event pushed by the device.
+fn dispatch(event: Response) {
+    match event {
+        Response::Data(payload) => log::info!("Event: {}", payload),
+        Response::Ack => log::debug!("Device acknowledged"),
+        Response::Nak => log::warn!("Device reported an error"),
+        Response::CrcMismatch => log::warn!("Dropped a corrupted event frame"),
+    }
+}
+
+fn test(device: &mut (impl Transport + ?Sized)) {
     log::debug!("Starting communication test...");
     log::debug!("Sending data to device...");
     match device.write("ping".as_bytes()) {
@@ -227,10 +505,9 @@ fn test(device: &mut Box<dyn serialport::SerialPort>) {
         }
     }
     log::debug!("Reading data from device...");
-    let mut read_buffer = [0u8; 1];
-    match device.read(&mut read_buffer) {
-        Ok(num) => {
-            log::debug!("Successfully read {} bytes from the device", num);
+    match read_response(device) {
+        Ok(response) => {
+            log::debug!("Device replied with {:?}", response);
             log::info!("Reading . . . . . [ OK ]");
         }
         Err(err) => {
@@ -241,7 +518,7 @@ fn test(device: &mut Box<dyn serialport::SerialPort>) {
     log::debug!("Communication test finished");
 }
 
-fn raw(device: &mut Box<dyn serialport::SerialPort>, payload: &str) {
+fn raw(device: &mut (impl Transport + ?Sized), payload: &str) {
     match device.write(payload.as_bytes()) {
         Ok(num) => log::info!("Successfully sent {} bytes to the device", num),
         Err(err) => log::error!("Failed to sent data to the device! Reason: {}", err),
@@ -257,52 +534,77 @@ fn raw(device: &mut Box<dyn serialport::SerialPort>, payload: &str) {
     }
 }
 
-fn next(device: &mut Box<dyn serialport::SerialPort>) -> Result<(), std::io::Error> {
-    device.write("NXT".as_bytes())?;
-    let mut read_buffer = [0u8; 1];
-    device.read(&mut read_buffer)?;
-    Ok(())
+/// Read framed bytes off the port until the parser yields a full [`Response`].
+fn read_response(
+    device: &mut (impl Transport + ?Sized),
+) -> Result<Response, std::io::Error> {
+    let mut parser = Parser::new();
+    let mut read_buffer = [0u8; 64];
+    loop {
+        let num = match device.read(&mut read_buffer) {
+            Ok(num) => num,
+            // Serial reports a read timeout as TimedOut, TCP as WouldBlock;
+            // surface both as TimedOut so the retry loop re-sends the command.
+            Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    err.to_string(),
+                ));
+            }
+            Err(err) => return Err(err),
+        };
+        if num == 0 {
+            // A TCP peer signals close with a zero-length read; surface it as
+            // an error so the retry loop can react instead of spinning.
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed by the device",
+            ));
+        }
+        if let Some(response) = parser.consume(&read_buffer[..num]).next() {
+            return Ok(response);
+        }
+    }
 }
 
-fn swap(device: &mut Box<dyn serialport::SerialPort>) -> Result<(), std::io::Error> {
-    device.write("SWP".as_bytes())?;
-    let mut read_buffer = [0u8; 1];
-    device.read(&mut read_buffer)?;
-    Ok(())
+fn next(device: &mut (impl Transport + ?Sized)) -> Result<Response, std::io::Error> {
+    device.write(&protocol::frame("NXT".as_bytes()))?;
+    read_response(device)
+}
+
+fn swap(device: &mut (impl Transport + ?Sized)) -> Result<Response, std::io::Error> {
+    device.write(&protocol::frame("SWP".as_bytes()))?;
+    read_response(device)
 }
 
 fn following(
-    device: &mut Box<dyn serialport::SerialPort>,
+    device: &mut (impl Transport + ?Sized),
     message: &str,
     color: &str,
-) -> Result<(), std::io::Error> {
-    device.write(
+) -> Result<Response, std::io::Error> {
+    device.write(&protocol::frame(
         format!(
             "FLW{};{}",
             message,
             color.strip_prefix("#").unwrap_or(color)
         )
         .as_bytes(),
-    )?;
-    let mut read_buffer = [0u8; 1];
-    device.read(&mut read_buffer)?;
-    Ok(())
+    ))?;
+    read_response(device)
 }
 
 fn add(
-    device: &mut Box<dyn serialport::SerialPort>,
+    device: &mut (impl Transport + ?Sized),
     message: &str,
     color: &str,
-) -> Result<(), std::io::Error> {
-    device.write(
+) -> Result<Response, std::io::Error> {
+    device.write(&protocol::frame(
         format!(
             "ADD{};{}",
             message,
             color.strip_prefix("#").unwrap_or(color)
         )
         .as_bytes(),
-    )?;
-    let mut read_buffer = [0u8; 1];
-    device.read(&mut read_buffer)?;
-    Ok(())
+    ))?;
+    read_response(device)
 }
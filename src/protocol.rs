@@ -0,0 +1,274 @@
+//! Incremental framing for device communication.
+//!
+//! Outgoing commands are framed exactly as the firmware was told to expect:
+//! `STX | payload | CRC16_hi | CRC16_lo | ETX`, with no byte-stuffing. The CRC
+//! is a CCITT CRC-16 (polynomial `0x1021`, initial value `0xFFFF`, no
+//! input/output reflection) computed over the payload bytes only.
+//!
+//! The receive path is deliberately asymmetric: since the binary CRC bytes of
+//! a reply can legitimately equal `ETX`, a plain `ETX` scan would truncate such
+//! frames early, so [`Parser`] also un-stuffs `ESC | (byte ^ 0x20)` sequences
+//! if the firmware emits them. It accumulates bytes coming off the port and
+//! yields typed [`Response`] values through the same incremental
+//! `parser.consume(&bytes)` iterator pattern the ublox serial code uses, so the
+//! caller never has to guess where a reply ends.
+
+/// Start-of-frame marker.
+pub const STX: u8 = 0x02;
+/// End-of-frame marker.
+pub const ETX: u8 = 0x03;
+/// Escape marker introducing a stuffed byte on the receive path.
+pub const ESC: u8 = 0x1B;
+/// XOR mask applied to a stuffed byte so markers never appear literally.
+const STUFF_MASK: u8 = 0x20;
+
+/// Single-byte acknowledgement payload.
+const ACK: u8 = 0x06;
+/// Single-byte negative-acknowledgement payload.
+const NAK: u8 = 0x15;
+
+/// A decoded device reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// The device accepted the command.
+    Ack,
+    /// The device rejected the command and wants it resent.
+    Nak,
+    /// A textual payload (e.g. a status line or the result of a query).
+    Data(String),
+    /// The frame was complete but its trailing CRC did not match the payload.
+    CrcMismatch,
+}
+
+/// Compute the CCITT CRC-16 of `data` (poly `0x1021`, init `0xFFFF`).
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Wrap a payload in a complete frame: `STX | payload | CRC16_hi | CRC16_lo |
+/// ETX`. Transmit frames are not byte-stuffed — that is the on-wire contract
+/// the firmware expects; see the module docs for why the receive path differs.
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let crc = crc16_ccitt(payload);
+    let mut out = Vec::with_capacity(payload.len() + 4);
+    out.push(STX);
+    out.extend_from_slice(payload);
+    out.push((crc >> 8) as u8);
+    out.push((crc & 0xFF) as u8);
+    out.push(ETX);
+    out
+}
+
+/// Incremental frame parser. Feed it whatever bytes arrive with
+/// [`Parser::consume`] and iterate the [`Response`] values it emits.
+#[derive(Default)]
+pub struct Parser {
+    buffer: Vec<u8>,
+}
+
+impl Parser {
+    /// Create an empty parser.
+    pub fn new() -> Self {
+        Parser { buffer: Vec::new() }
+    }
+
+    /// Append `bytes` to the internal buffer and return an iterator over the
+    /// complete frames that can now be decoded. Incomplete trailing bytes are
+    /// kept for the next call.
+    pub fn consume(&mut self, bytes: &[u8]) -> ConsumeIter<'_> {
+        self.buffer.extend_from_slice(bytes);
+        ConsumeIter { parser: self }
+    }
+}
+
+/// Iterator returned by [`Parser::consume`], draining one frame per step.
+pub struct ConsumeIter<'a> {
+    parser: &'a mut Parser,
+}
+
+impl Iterator for ConsumeIter<'_> {
+    type Item = Response;
+
+    fn next(&mut self) -> Option<Response> {
+        let buf = &mut self.parser.buffer;
+        loop {
+            // Drop any noise preceding the next start-of-frame marker.
+            let start = buf.iter().position(|&b| b == STX)?;
+            if start > 0 {
+                buf.drain(0..start);
+            }
+            // Un-stuff the body until the terminating ETX. A stray STX before
+            // then means the previous frame was truncated; resync on it.
+            let mut decoded = Vec::new();
+            let mut escaped = false;
+            let mut terminator = None;
+            let mut resync = None;
+            for (idx, &byte) in buf.iter().enumerate().skip(1) {
+                if escaped {
+                    decoded.push(byte ^ STUFF_MASK);
+                    escaped = false;
+                } else if byte == ESC {
+                    escaped = true;
+                } else if byte == ETX {
+                    terminator = Some(idx);
+                    break;
+                } else if byte == STX {
+                    resync = Some(idx);
+                    break;
+                } else {
+                    decoded.push(byte);
+                }
+            }
+            if let Some(idx) = resync {
+                buf.drain(0..idx);
+                continue;
+            }
+            // No ETX yet: wait for the rest of the frame to arrive.
+            let end = terminator?;
+            buf.drain(0..=end);
+            // A valid body carries at least the two trailing CRC bytes.
+            if decoded.len() < 2 {
+                return Some(Response::CrcMismatch);
+            }
+            let crc_lo = decoded.pop().unwrap();
+            let crc_hi = decoded.pop().unwrap();
+            let expected = ((crc_hi as u16) << 8) | crc_lo as u16;
+            if crc16_ccitt(&decoded) != expected {
+                return Some(Response::CrcMismatch);
+            }
+            return Some(decode(&decoded));
+        }
+    }
+}
+
+/// Interpret a verified payload as a [`Response`].
+fn decode(payload: &[u8]) -> Response {
+    match payload {
+        [ACK] => Response::Ack,
+        [NAK] => Response::Nak,
+        _ => Response::Data(String::from_utf8_lossy(payload).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a reply the way a firmware that byte-stuffs its frames would, so
+    /// the receive path can be exercised regardless of the CRC byte values.
+    fn stuffed_frame(payload: &[u8]) -> Vec<u8> {
+        let crc = crc16_ccitt(payload);
+        let body = [payload, &[(crc >> 8) as u8, (crc & 0xFF) as u8]].concat();
+        let mut out = vec![STX];
+        for &byte in &body {
+            if byte == STX || byte == ETX || byte == ESC {
+                out.push(ESC);
+                out.push(byte ^ STUFF_MASK);
+            } else {
+                out.push(byte);
+            }
+        }
+        out.push(ETX);
+        out
+    }
+
+    #[test]
+    fn crc16_known_answer() {
+        // The canonical CRC-16/CCITT-FALSE check value for "123456789".
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn frame_has_plain_wire_format() {
+        // Transmit frames carry the payload and CRC verbatim, with no stuffing.
+        let crc = crc16_ccitt(b"NXT");
+        assert_eq!(
+            frame(b"NXT"),
+            vec![STX, b'N', b'X', b'T', (crc >> 8) as u8, (crc & 0xFF) as u8, ETX]
+        );
+    }
+
+    #[test]
+    fn receive_roundtrip_data() {
+        let mut parser = Parser::new();
+        let responses: Vec<_> = parser.consume(&stuffed_frame(b"ADDhello;FF0000")).collect();
+        assert_eq!(
+            responses,
+            vec![Response::Data("ADDhello;FF0000".to_string())]
+        );
+    }
+
+    #[test]
+    fn ack_and_nak_decode() {
+        let mut parser = Parser::new();
+        assert_eq!(
+            parser.consume(&stuffed_frame(&[ACK])).next(),
+            Some(Response::Ack)
+        );
+        assert_eq!(
+            parser.consume(&stuffed_frame(&[NAK])).next(),
+            Some(Response::Nak)
+        );
+    }
+
+    #[test]
+    fn split_reads_reassemble() {
+        let framed = stuffed_frame(b"NXT");
+        let (head, tail) = framed.split_at(2);
+        let mut parser = Parser::new();
+        assert!(parser.consume(head).next().is_none());
+        assert_eq!(
+            parser.consume(tail).next(),
+            Some(Response::Data("NXT".to_string()))
+        );
+    }
+
+    #[test]
+    fn garbage_prefix_is_skipped() {
+        let mut bytes = vec![0x00, 0xFF, 0x11];
+        bytes.extend_from_slice(&stuffed_frame(b"SWP"));
+        let mut parser = Parser::new();
+        assert_eq!(
+            parser.consume(&bytes).next(),
+            Some(Response::Data("SWP".to_string()))
+        );
+    }
+
+    #[test]
+    fn stuffed_bytes_roundtrip_on_receive() {
+        // Bytes colliding with the markers survive the receive un-stuffing.
+        let payload = [STX, ETX, ESC, 0x41, 0x42];
+        let framed = stuffed_frame(&payload);
+        // The terminating ETX is the only unescaped one in the frame.
+        assert_eq!(framed.iter().filter(|&&b| b == ETX).count(), 1);
+        let mut parser = Parser::new();
+        let expected = String::from_utf8_lossy(&payload).into_owned();
+        assert_eq!(
+            parser.consume(&framed).next(),
+            Some(Response::Data(expected))
+        );
+    }
+
+    #[test]
+    fn corrupted_crc_reports_mismatch() {
+        let mut framed = stuffed_frame(b"PING");
+        // Flip a payload byte so the trailing CRC no longer matches.
+        framed[1] ^= 0xFF;
+        let mut parser = Parser::new();
+        assert_eq!(
+            parser.consume(&framed).next(),
+            Some(Response::CrcMismatch)
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Pluggable link backends.
+//!
+//! The command encoding only needs something it can read from and write to,
+//! so it is expressed against the [`Transport`] trait rather than a concrete
+//! serial port. Two backends are provided: [`SerialTransport`] for a locally
+//! attached device and [`TcpTransport`] for a device reachable through a
+//! serial-over-TCP bridge.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// A bidirectional link to a threader device.
+///
+/// The [`handshake`](Transport::handshake) hook lets each backend bring its
+/// link into a known state before the first command, while
+/// [`try_clone`](Transport::try_clone) hands the daemon reader thread an
+/// independent handle on the same link.
+pub trait Transport: Read + Write + Send {
+    /// Prepare the link for communication.
+    fn handshake(&mut self) -> io::Result<()>;
+    /// Obtain an independent handle on the same link.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+/// Locally attached serial port.
+pub struct SerialTransport(pub Box<dyn serialport::SerialPort>);
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn handshake(&mut self) -> io::Result<()> {
+        self.0.write_data_terminal_ready(false).map_err(serial_to_io)?;
+        self.0.write_request_to_send(false).map_err(serial_to_io)?;
+        Ok(())
+    }
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(SerialTransport(
+            self.0.try_clone().map_err(serial_to_io)?,
+        )))
+    }
+}
+
+/// Device reached through a serial-over-TCP bridge.
+pub struct TcpTransport(pub TcpStream);
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn handshake(&mut self) -> io::Result<()> {
+        // A raw socket needs no modem-control lines; the bridge handles them.
+        Ok(())
+    }
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpTransport(self.0.try_clone()?)))
+    }
+}
+
+/// Connect to a remote device over TCP, mirroring the serial read timeout.
+pub fn connect_tcp(address: &str, timeout: Duration) -> io::Result<Box<dyn Transport>> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(timeout))?;
+    Ok(Box::new(TcpTransport(stream)))
+}
+
+/// Map a `serialport` error onto an [`io::Error`] so both backends share one
+/// error type.
+fn serial_to_io(err: serialport::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}